@@ -1,42 +1,260 @@
 use std::fs::{File, read_dir};
 use std::env;
 use std::io::{Read, Write};
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+use std::thread;
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 use rodio::{Decoder, OutputStream, Sink, Source};
 use device_query::{DeviceQuery, DeviceState};
 use rand::random;
 
+mod menu;
+
+// Emitted by the input thread (and, in --menu mode, the menu thread)
+// and consumed by the audio loop on the main thread, so neither
+// producer ever blocks on playback.
+pub enum InputEvent {
+  KeyDown(String),
+  KeyUp(String),
+  Toggle,
+  NextPack,
+  PrevPack,
+  SelectPack(usize),
+  SetVolume(f32),
+  SetPitchStart(f32),
+  SetPitchRange(f32),
+  SetPitchSteps(f32),
+  SetFastThreshold(f32),
+  SaveConfig,
+  Terminate
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+// Polls device_query for key state and turns it into InputEvents,
+// debouncing the control keybinds itself so the audio loop only ever
+// sees clean edges.
+fn spawn_input_thread(settings: &Settings, tx: mpsc::Sender<InputEvent>) {
+  let terminate_keys = settings.terminate.clone();
+  let toggle_keys = settings.toggle.clone();
+  let prev_pack_keys = settings.previous_sound_pack.clone();
+  let next_pack_keys = settings.next_sound_pack.clone();
+
+  thread::spawn(move || {
+    let device_state = DeviceState::new();
+    let mut previous_keys: HashSet<String> = HashSet::new();
+    let mut toggled = false;
+    let mut switched_pack = false;
+
+    loop {
+      let keys = device_state.get_keys();
+      let key_names: Vec<String> = keys.iter().map(|x| x.to_string()).collect();
+
+      if let Some(keybind) = &terminate_keys {
+        if keybind.len() == key_names.len() &&
+          keybind.iter().all(|x| key_names.contains(x)) {
+            let _ = tx.send(InputEvent::Terminate);
+            return;
+        }
+      }
+      if let Some(keybind) = &toggle_keys {
+        if !toggled && keybind.len() == key_names.len() &&
+          keybind.iter().all(|x| key_names.contains(x)) {
+            toggled = true;
+            if tx.send(InputEvent::Toggle).is_err() { return; }
+        }
+      }
+      if key_names.is_empty() { toggled = false; switched_pack = false; }
+      if let Some(keybind) = &prev_pack_keys {
+        if !switched_pack && keybind.len() == key_names.len() &&
+          keybind.iter().all(|x| key_names.contains(x)) {
+            switched_pack = true;
+            if tx.send(InputEvent::PrevPack).is_err() { return; }
+        }
+      }
+      if let Some(keybind) = &next_pack_keys {
+        if !switched_pack && keybind.len() == key_names.len() &&
+          keybind.iter().all(|x| key_names.contains(x)) {
+            switched_pack = true;
+            if tx.send(InputEvent::NextPack).is_err() { return; }
+        }
+      }
+
+      let current_keys: HashSet<String> = key_names.into_iter().collect();
+      for key in current_keys.difference(&previous_keys) {
+        if tx.send(InputEvent::KeyDown(key.clone())).is_err() { return; }
+      }
+      for key in previous_keys.difference(&current_keys) {
+        if tx.send(InputEvent::KeyUp(key.clone())).is_err() { return; }
+      }
+      previous_keys = current_keys;
+
+      thread::sleep(POLL_INTERVAL);
+    }
+  });
+}
+
+// Where a sound pack's samples come from. `Local` is a plain folder
+// already on disk; `Url`/`Archive` are fetched and cached the first
+// time they're used, then resolved to a local folder like any other.
+// Adjacently tagged rather than internally tagged: serde can't
+// (de)serialize an internally tagged newtype variant wrapping a bare
+// string, since there's nowhere to put the tag alongside it.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(tag = "type", content = "path", rename_all = "lowercase")]
+pub enum PackSource {
+  Local(String),
+  Url(String),
+  Archive(String)
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum SoundPackSettings {
   Basic(String),
   Advanced {
-    name: String,
+    source: PackSource,
     volume: Option<f32>,
     pitch_start: Option<f32>,
     pitch_range: Option<f32>,
     pitch_steps: Option<f32>,
-    fast_threshold: Option<f32>
+    fast_threshold: Option<f32>,
+    max_voices: Option<usize>,
+    release_folder: Option<String>,
+    key_map: Option<HashMap<String, String>>,
+    release_key_map: Option<HashMap<String, String>>,
+    reverb_delay_ms: Option<u32>,
+    reverb_decay: Option<f32>,
+    reverb_mix: Option<f32>
   }
 }
 
-fn get_path(settings: &SoundPackSettings) -> &String {
+fn pack_source(settings: &SoundPackSettings) -> PackSource {
   match settings {
-    SoundPackSettings::Basic(path) => return path,
-    SoundPackSettings::Advanced { name, volume: _,
+    SoundPackSettings::Basic(path) => PackSource::Local(path.clone()),
+    SoundPackSettings::Advanced { source, volume: _,
       pitch_start: _, pitch_range: _, pitch_steps: _,
-      fast_threshold: _ } => return name
+      fast_threshold: _, max_voices: _, release_folder: _,
+      key_map: _, release_key_map: _, reverb_delay_ms: _,
+      reverb_decay: _, reverb_mix: _ } => source.clone()
+  }
+}
+
+// A pack's live playback settings, resolved against the global
+// defaults for whatever it doesn't override itself. Shared by the
+// press and release paths so both honor `SoundPackSettings::Advanced`
+// the same way.
+struct PackPlayback {
+  volume: f32,
+  pitch_start: f32,
+  pitch_range: f32,
+  pitch_steps: f32,
+  fast_threshold: f32,
+  max_voices: usize,
+  reverb_delay_ms: u32,
+  reverb_decay: f32,
+  reverb_mix: f32
+}
+
+fn resolve_pack_settings(pack: &SoundPackSettings, settings: &Settings) -> PackPlayback {
+  match pack {
+    SoundPackSettings::Advanced { source: _, volume, pitch_start,
+      pitch_range, pitch_steps, fast_threshold, max_voices,
+      release_folder: _, key_map: _, release_key_map: _,
+      reverb_delay_ms, reverb_decay, reverb_mix } => PackPlayback {
+        volume: volume.unwrap_or(settings.volume.unwrap_or(1.0)),
+        pitch_start: pitch_start.unwrap_or(settings.pitch_start.unwrap_or(0.5)),
+        pitch_range: pitch_range.unwrap_or(settings.pitch_range.unwrap_or(0.5)),
+        pitch_steps: pitch_steps.unwrap_or(settings.pitch_steps.unwrap_or(0.005)),
+        fast_threshold: fast_threshold.unwrap_or(settings.fast_threshold.unwrap_or(1.0)),
+        max_voices: max_voices.unwrap_or(settings.max_voices.unwrap_or(DEFAULT_MAX_VOICES)),
+        reverb_delay_ms: reverb_delay_ms.unwrap_or(settings.reverb_delay_ms.unwrap_or(0)),
+        reverb_decay: reverb_decay.unwrap_or(settings.reverb_decay.unwrap_or(0.0)),
+        reverb_mix: reverb_mix.unwrap_or(settings.reverb_mix.unwrap_or(0.0))
+      },
+    SoundPackSettings::Basic(_) => PackPlayback {
+      volume: settings.volume.unwrap_or(1.0),
+      pitch_start: settings.pitch_start.unwrap_or(0.5),
+      pitch_range: settings.pitch_range.unwrap_or(0.5),
+      pitch_steps: settings.pitch_steps.unwrap_or(0.005),
+      fast_threshold: settings.fast_threshold.unwrap_or(1.0),
+      max_voices: settings.max_voices.unwrap_or(DEFAULT_MAX_VOICES),
+      reverb_delay_ms: settings.reverb_delay_ms.unwrap_or(0),
+      reverb_decay: settings.reverb_decay.unwrap_or(0.0),
+      reverb_mix: settings.reverb_mix.unwrap_or(0.0)
+    }
+  }
+}
+
+fn source_raw(source: &PackSource) -> &String {
+  match source {
+    PackSource::Local(path) => path,
+    PackSource::Url(url) => url,
+    PackSource::Archive(url) => url
   }
 }
 
 fn get_name(settings: &SoundPackSettings) -> String {
-  let path = get_path(settings);
-  if let Some(idx) = path.chars().rev().position(|c| c == '/' || c == '\\') {
-    return path[(path.chars().count() - idx - 1)..].to_string();
+  let raw = source_raw(&pack_source(settings));
+  if let Some(idx) = raw.chars().rev().position(|c| c == '/' || c == '\\') {
+    return raw[(raw.chars().count() - idx - 1)..].to_string();
   }
   else {
-    return path.clone();
+    return raw.clone();
+  }
+}
+
+// Cache directory for a remote source, keyed by a hash of its URL so
+// repeat runs reuse the same download instead of refetching it.
+fn cache_dir_for(url: &str) -> std::path::PathBuf {
+  use std::collections::hash_map::DefaultHasher;
+  use std::hash::{Hash, Hasher};
+  let mut hasher = DefaultHasher::new();
+  url.hash(&mut hasher);
+  let key = format!("{:x}", hasher.finish());
+  dirs::cache_dir().unwrap_or_else(env::temp_dir).join("ksfx").join(key)
+}
+
+fn download_to(url: &str, dest: &Path) {
+  let response = reqwest::blocking::get(url)
+    .expect(&format!("Could not download sound pack from \"{}\"", url));
+  let bytes = response.bytes()
+    .expect(&format!("Could not read sound pack downloaded from \"{}\"", url));
+  std::fs::write(dest, &bytes)
+    .expect(&format!("Could not write downloaded sound pack to \"{}\"", dest.display()));
+}
+
+// Resolves a `PackSource` to a local folder, downloading and (for
+// archives) unpacking into a cache directory the first time around.
+fn resolve_source(source: &PackSource) -> String {
+  match source {
+    PackSource::Local(path) => path.clone(),
+    PackSource::Url(url) => {
+      let dir = cache_dir_for(url);
+      std::fs::create_dir_all(&dir).expect("Could not create sound pack cache directory");
+      let file_name = url.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("sample");
+      let file_path = dir.join(file_name);
+      if !file_path.exists() {
+        download_to(url, &file_path);
+      }
+      dir.to_string_lossy().to_string()
+    }
+    PackSource::Archive(url) => {
+      let dir = cache_dir_for(url);
+      if !dir.exists() {
+        std::fs::create_dir_all(&dir).expect("Could not create sound pack cache directory");
+        let archive_path = dir.join("archive.zip");
+        download_to(url, &archive_path);
+        let file = File::open(&archive_path).expect("Could not open downloaded sound pack archive");
+        let mut archive = zip::ZipArchive::new(file)
+          .expect("Could not read downloaded sound pack archive");
+        archive.extract(&dir).expect("Could not unpack downloaded sound pack archive");
+      }
+      dir.to_string_lossy().to_string()
+    }
   }
 }
 
@@ -51,29 +269,268 @@ pub struct Settings {
   pitch_start: Option<f32>,
   pitch_range: Option<f32>,
   pitch_steps: Option<f32>,
-  fast_threshold: Option<f32>
+  fast_threshold: Option<f32>,
+  max_voices: Option<usize>,
+  output_device: Option<String>,
+  reverb_delay_ms: Option<u32>,
+  reverb_decay: Option<f32>,
+  reverb_mix: Option<f32>
 }
 
-fn main() {
-  let (_stream, stream_handle) = OutputStream::try_default()
-    .expect("Could not get default output stream handle");
+// Owns the live output stream so it can be torn down and reopened if
+// the device disappears mid-session (headphones unplugged, etc.).
+struct AudioOutput {
+  _stream: OutputStream,
+  handle: rodio::OutputStreamHandle
+}
 
-  let mut active = true;
+impl AudioOutput {
+  fn open(device_name: &Option<String>) -> AudioOutput {
+    use rodio::cpal::traits::{HostTrait, DeviceTrait};
 
-  let sink = Sink::try_new(&stream_handle).unwrap();
-  let device_state = DeviceState::new();
+    let matched = device_name.as_ref().and_then(|name| {
+      let host = rodio::cpal::default_host();
+      host.output_devices().ok()?
+        .find(|device| device.name().map(|n| &n == name).unwrap_or(false))
+    });
 
+    let (stream, handle) = match matched {
+      Some(device) => OutputStream::try_from_device(&device)
+        .unwrap_or_else(|_| OutputStream::try_default()
+          .expect("Could not get any output stream handle")),
+      None => OutputStream::try_default()
+        .expect("Could not get default output stream handle")
+    };
 
-  let config_path;
-  if let Some(path) = env::args().nth(1) {
-    config_path = path;
+    AudioOutput { _stream: stream, handle }
+  }
+
+  // Whether the device we're supposed to be bound to (or, for the
+  // system default, any output device at all) is still enumerable.
+  // cpal surfaces a vanished device as a silent stream error rather
+  // than a panic, so this is the actual signal `play_with_recovery`
+  // relies on to notice the loss.
+  fn is_present(device_name: &Option<String>) -> bool {
+    use rodio::cpal::traits::{HostTrait, DeviceTrait};
+    let host = rodio::cpal::default_host();
+    match device_name {
+      Some(name) => host.output_devices()
+        .map(|mut devices| devices.any(|device| device.name().map(|n| &n == name).unwrap_or(false)))
+        .unwrap_or(false),
+      None => host.default_output_device().is_some()
+    }
+  }
+}
+
+type Sample = rodio::source::Buffered<Decoder<File>>;
+type BoxedSample = Box<dyn Source<Item = <Sample as Iterator>::Item> + Send>;
+
+// Loads every file in `path` if it's a folder (for the usual random
+// selection), or just that one file if it's a specific sample.
+fn load_samples(path: &str) -> Vec<Sample> {
+  let full_path = Path::new(path);
+  let mut samples = Vec::new();
+  if full_path.is_dir() {
+    let dir = read_dir(full_path)
+      .expect(&format!("Sound pack folder not found at \"{}\"", path));
+    for entry in dir.into_iter() {
+      samples.push(Decoder::new(File::open(entry.unwrap().path()).unwrap()).unwrap().buffered());
+    }
   }
   else {
-    config_path = String::from("ksfx.json");
+    samples.push(Decoder::new(File::open(full_path).unwrap()).unwrap().buffered());
+  }
+  samples
+}
+
+struct PackSounds {
+  press: Vec<Sample>,
+  release: Vec<Sample>,
+  key_map: HashMap<String, Vec<Sample>>,
+  release_key_map: HashMap<String, Vec<Sample>>
+}
+
+const DEFAULT_MAX_VOICES: usize = 8;
+
+struct VoicePool {
+  sinks: Vec<Sink>,
+  started: Vec<Instant>
+}
+
+impl VoicePool {
+  fn new(stream_handle: &rodio::OutputStreamHandle, size: usize) -> Self {
+    let mut pool = VoicePool { sinks: Vec::new(), started: Vec::new() };
+    pool.resize(stream_handle, size);
+    pool
   }
 
+  fn resize(&mut self, stream_handle: &rodio::OutputStreamHandle, size: usize) {
+    let size = size.max(1);
+    while self.sinks.len() < size {
+      self.sinks.push(Sink::try_new(stream_handle).unwrap());
+      self.started.push(Instant::now());
+    }
+    while self.sinks.len() > size {
+      self.sinks.pop();
+      self.started.pop();
+    }
+  }
+
+  // Picks a free voice if one is available, otherwise steals the
+  // oldest-started voice so a burst of keystrokes never blocks.
+  fn acquire(&mut self) -> usize {
+    if let Some(idx) = self.sinks.iter().position(|sink| sink.empty()) {
+      return idx;
+    }
+    self.started.iter().enumerate()
+      .min_by_key(|(_, started)| **started)
+      .map(|(idx, _)| idx)
+      .unwrap_or(0)
+  }
+
+  fn play<S>(&mut self, source: S, speed: f32, volume: f32)
+  where S: Source + Send + 'static, S::Item: rodio::Sample + Send {
+    let idx = self.acquire();
+    self.sinks[idx].stop();
+    self.sinks[idx].set_speed(speed);
+    self.sinks[idx].set_volume(volume);
+    self.sinks[idx].append(source);
+    self.started[idx] = Instant::now();
+  }
+}
+
+// Feeds a delayed, decayed copy of the signal back into itself to
+// produce a simple feedback-delay reverb/echo, in the spirit of the
+// aux-slot reverb in the bevy_openal integration but done in software
+// over the raw sample stream. Wraps a buffered sample before it
+// reaches the sink, so it composes with the existing pitch/volume path.
+// Once the dry input ends, the adapter keeps draining (and re-decaying)
+// its own feedback buffer for a few more passes so the echo has a real
+// tail instead of cutting off the instant the original sample does.
+const REVERB_TAIL_EPSILON: f32 = 0.0005;
+
+struct Reverb<S: Source> where S::Item: rodio::Sample {
+  input: S,
+  buffer: std::collections::VecDeque<S::Item>,
+  decay: f32,
+  mix: f32,
+  channels: u16,
+  sample_rate: u32,
+  extra_duration: Duration,
+  input_done: bool,
+  tail_remaining: usize
+}
+
+impl<S: Source> Reverb<S> where S::Item: rodio::Sample {
+  fn new(input: S, delay_ms: u32, decay: f32, mix: f32) -> Self {
+    let channels = input.channels().max(1) as usize;
+    let sample_rate = input.sample_rate().max(1) as usize;
+    let delay_samples = (delay_ms as usize * sample_rate / 1000 * channels).max(channels);
+    let decay = decay.clamp(0.0, 0.99);
+
+    // At least one buffer's worth always needs to drain, since it holds
+    // delayed audio that hasn't reached the output yet; with decay > 0
+    // we keep looping until the feedback has died down below epsilon.
+    let tail_loops = if decay > 0.0 {
+      ((REVERB_TAIL_EPSILON.ln() / decay.ln()).ceil().max(1.0) as usize).max(1)
+    } else {
+      1
+    };
+    let tail_remaining = delay_samples * tail_loops;
+    let extra_duration = Duration::from_secs_f64(
+      tail_remaining as f64 / (sample_rate as f64 * channels as f64));
+
+    let buffer = std::iter::repeat(S::Item::zero_value()).take(delay_samples).collect();
+    Reverb {
+      input, buffer, decay, mix: mix.clamp(0.0, 1.0),
+      channels: channels as u16, sample_rate: sample_rate as u32,
+      extra_duration, input_done: false, tail_remaining
+    }
+  }
+}
+
+impl<S: Source> Iterator for Reverb<S> where S::Item: rodio::Sample {
+  type Item = S::Item;
+  fn next(&mut self) -> Option<S::Item> {
+    let dry = if self.input_done { None } else { self.input.next() };
+    let dry = match dry {
+      Some(sample) => sample,
+      None => {
+        self.input_done = true;
+        if self.tail_remaining == 0 { return None; }
+        self.tail_remaining -= 1;
+        S::Item::zero_value()
+      }
+    };
+
+    let delayed = self.buffer.pop_front().unwrap_or_else(S::Item::zero_value);
+    self.buffer.push_back(dry.saturating_add(delayed.amplify(self.decay)));
+    Some(dry.amplify(1.0 - self.mix).saturating_add(delayed.amplify(self.mix)))
+  }
+}
+
+impl<S: Source> Source for Reverb<S> where S::Item: rodio::Sample {
+  fn current_frame_len(&self) -> Option<usize> {
+    if self.input_done { Some(self.tail_remaining) } else { self.input.current_frame_len() }
+  }
+  fn channels(&self) -> u16 { self.channels }
+  fn sample_rate(&self) -> u32 { self.sample_rate }
+  fn total_duration(&self) -> Option<Duration> {
+    self.input.total_duration().map(|d| d + self.extra_duration)
+  }
+}
+
+// Plays a sample, and if the output device has died underneath us,
+// reopens it (falling back to the system default) and rebuilds the
+// voice pool before retrying. Checks device presence up front since
+// that's the signal that actually fires when a device disappears
+// mid-session; the panic catch below only covers the rarer backend
+// that reports the failure by panicking instead of going silent.
+fn play_with_recovery(voices: &mut VoicePool, output: &mut AudioOutput,
+  device_name: &Option<String>, max_voices: usize, sample: &Sample, speed: f32, volume: f32,
+  reverb_delay_ms: u32, reverb_decay: f32, reverb_mix: f32) {
+  if !AudioOutput::is_present(device_name) {
+    eprintln!("Warning: lost the audio output device, reopening it...");
+    *output = AudioOutput::open(device_name);
+    *voices = VoicePool::new(&output.handle, max_voices);
+  }
+
+  let build_source = || -> BoxedSample {
+    if reverb_mix > 0.0 {
+      Box::new(Reverb::new(sample.clone(), reverb_delay_ms, reverb_decay, reverb_mix))
+    }
+    else {
+      Box::new(sample.clone())
+    }
+  };
+
+  let played = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+    voices.play(build_source(), speed, volume);
+  }));
+
+  if played.is_err() {
+    eprintln!("Warning: lost the audio output device, reopening it...");
+    *output = AudioOutput::open(device_name);
+    *voices = VoicePool::new(&output.handle, max_voices);
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+      voices.play(build_source(), speed, volume);
+    }));
+  }
+}
+
+fn main() {
+  let mut active = true;
+
+  let cli_args: Vec<String> = env::args().skip(1).collect();
+  let menu_mode = cli_args.iter().any(|arg| arg == "--menu");
+
+  let config_path = cli_args.iter()
+    .find(|arg| !arg.starts_with("--"))
+    .cloned()
+    .unwrap_or_else(|| String::from("ksfx.json"));
+
   let mut serialized_config = String::new();
-  let settings: Settings;
+  let mut settings: Settings;
   if let Ok(mut file) = File::open(&config_path) {
     file.read_to_string(&mut serialized_config)
       .expect(&format!("Could not read from config file at \"{}\"", config_path));
@@ -93,7 +550,8 @@ fn main() {
   \"pitch_start\": 0.5,
   \"pitch_range\": 0.5,
   \"pitch_steps\": 0.005,
-  \"fast_threshold\": 1.0
+  \"fast_threshold\": 1.0,
+  \"max_voices\": 8
 }"
     );
     let file = File::create(&config_path);
@@ -110,116 +568,178 @@ fn main() {
       terminate: Some(vec![String::from("F2")]),
       toggle: Some(vec![String::from("F3")]), volume: Some(1.0),
       pitch_start: Some(0.5), pitch_range: Some(0.5),
-      pitch_steps: Some(0.005), fast_threshold: Some(1.0) };
+      pitch_steps: Some(0.005), fast_threshold: Some(1.0),
+      max_voices: Some(DEFAULT_MAX_VOICES), output_device: None,
+      reverb_delay_ms: None, reverb_decay: None, reverb_mix: None };
   }
 
 
 
   let mut sound_packs = Vec::new();
   for sound_pack in settings.sound_packs.iter() {
-    let path = get_path(sound_pack);
-    let mut sounds = Vec::new();
-    let dir = read_dir(path)
-      .expect(&format!("Sound pack folder not found at \"{}\"", path));
-    for entry in dir.into_iter() {
-      sounds.push(Decoder::new(File::open(entry.unwrap().path()).unwrap()).unwrap().buffered());
+    let resolved_path = resolve_source(&pack_source(sound_pack));
+    let press = load_samples(&resolved_path);
+
+    let mut release = Vec::new();
+    let mut key_map = HashMap::new();
+    let mut release_key_map = HashMap::new();
+    if let SoundPackSettings::Advanced { release_folder, key_map: map,
+      release_key_map: release_map, .. } = sound_pack {
+      if let Some(folder) = release_folder {
+        release = load_samples(folder);
+      }
+      if let Some(map) = map {
+        for (key, path) in map.iter() {
+          key_map.insert(key.clone(), load_samples(path));
+        }
+      }
+      if let Some(map) = release_map {
+        for (key, path) in map.iter() {
+          release_key_map.insert(key.clone(), load_samples(path));
+        }
+      }
     }
-    sound_packs.push(sounds);
+
+    sound_packs.push(PackSounds { press, release, key_map, release_key_map });
   }
 
 
 
-  let mut previous_key_amt = 0;
   let mut last_press = Instant::now();
   let mut pitch = settings.pitch_start.unwrap_or(0.5);
-  let mut toggled = false;
-  let mut switched_pack = false;
   let mut current_sound_pack = 0;
 
-  loop {
-    let keys =  device_state.get_keys();
-    let key_names: Vec<String> = keys.clone().iter().map(|x| x.to_string()).collect();
-    
-    if let Some(keybind) = settings.terminate.clone() {
-      if keybind.len() == key_names.len() &&
-        keybind.iter().all(|x| key_names.contains(x)) {
-          return println!("Program terminated!");
-      }
-    }
-    if let Some(keybind) = settings.toggle.clone() {
-      if !toggled && keybind.len() == key_names.len() &&
-        keybind.iter().all(|x| key_names.contains(x)) {
-          toggled = true;
-          active = !active;
-          println!("Toggled keyboard sound effects.");
-      }
-    }
-    if keys.len() == 0 { toggled = false; switched_pack = false; }
-    if let Some(keybind) = settings.previous_sound_pack.clone() {
-      if !switched_pack && keybind.len() == key_names.len() &&
-        keybind.iter().all(|x| key_names.contains(x)) {
-          switched_pack = true;
-          if current_sound_pack == 0 {
-            current_sound_pack = sound_packs.len();
-          }
-          else {
-            current_sound_pack -= 1;
-          }
-          current_sound_pack %= sound_packs.len();
-          println!("Changed sound pack to \"{}\"", get_name(&settings.sound_packs[current_sound_pack]));
+  let mut output = AudioOutput::open(&settings.output_device);
+  let mut voices = VoicePool::new(&output.handle,
+    settings.max_voices.unwrap_or(DEFAULT_MAX_VOICES));
+
+  let (tx, events) = mpsc::channel();
+  spawn_input_thread(&settings, tx.clone());
+
+  if menu_mode {
+    let menu_state = menu::MenuState {
+      pack_names: settings.sound_packs.iter().map(get_name).collect(),
+      volume: settings.volume.unwrap_or(1.0),
+      pitch_start: settings.pitch_start.unwrap_or(0.5),
+      pitch_range: settings.pitch_range.unwrap_or(0.5),
+      pitch_steps: settings.pitch_steps.unwrap_or(0.005),
+      fast_threshold: settings.fast_threshold.unwrap_or(1.0)
+    };
+    let menu_tx = tx.clone();
+    thread::spawn(move || menu::run(menu_state, menu_tx));
+  }
+  drop(tx);
+
+  for event in events {
+    match event {
+      InputEvent::Terminate => {
+        println!("Program terminated!");
+        return;
       }
-    }
-    if let Some(keybind) = settings.next_sound_pack.clone() {
-      if !switched_pack && keybind.len() == key_names.len() &&
-        keybind.iter().all(|x| key_names.contains(x)) {
-          switched_pack = true;
-          current_sound_pack += 1;
-          current_sound_pack %= sound_packs.len();
+      InputEvent::Toggle => {
+        active = !active;
+        println!("Toggled keyboard sound effects.");
+      }
+      InputEvent::PrevPack => {
+        if current_sound_pack == 0 {
+          current_sound_pack = sound_packs.len();
+        }
+        else {
+          current_sound_pack -= 1;
+        }
+        current_sound_pack %= sound_packs.len();
+        println!("Changed sound pack to \"{}\"", get_name(&settings.sound_packs[current_sound_pack]));
+      }
+      InputEvent::NextPack => {
+        current_sound_pack += 1;
+        current_sound_pack %= sound_packs.len();
+        println!("Changed sound pack to \"{}\"", get_name(&settings.sound_packs[current_sound_pack]));
+      }
+      InputEvent::SelectPack(idx) => {
+        if idx < sound_packs.len() {
+          current_sound_pack = idx;
           println!("Changed sound pack to \"{}\"", get_name(&settings.sound_packs[current_sound_pack]));
+        }
       }
-    }
+      InputEvent::SetVolume(value) => settings.volume = Some(value),
+      InputEvent::SetPitchStart(value) => settings.pitch_start = Some(value),
+      InputEvent::SetPitchRange(value) => settings.pitch_range = Some(value),
+      InputEvent::SetPitchSteps(value) => settings.pitch_steps = Some(value),
+      InputEvent::SetFastThreshold(value) => settings.fast_threshold = Some(value),
+      InputEvent::SaveConfig => {
+        match serde_json::to_string_pretty(&settings) {
+          Ok(json) => match File::create(&config_path).and_then(|mut f| f.write_all(json.as_bytes())) {
+            Ok(_) => println!("Saved settings to \"{}\"", config_path),
+            Err(err) => println!("Could not save settings to \"{}\": {}", config_path, err)
+          },
+          Err(err) => println!("Could not serialize settings: {}", err)
+        }
+      }
+      InputEvent::KeyDown(key) => {
+        if !active { continue; }
 
-    if !active { continue; }
+        let playback = resolve_pack_settings(&settings.sound_packs[current_sound_pack], &settings);
 
-    if keys.len() > previous_key_amt {
-      let selection = random::<f32>() * sound_packs[current_sound_pack].len() as f32;
-      let (volume, pitch_start,
-        pitch_range, pitch_steps, fast_threshold);
-      match settings.sound_packs[current_sound_pack] {
-        SoundPackSettings::Advanced { name: _, volume: a, pitch_start: b,
-          pitch_range: c, pitch_steps: d, fast_threshold: e } => {
-            volume = a.unwrap_or(settings.volume.unwrap_or(1.0));
-            pitch_start = b.unwrap_or(settings.pitch_start.unwrap_or(0.5));
-            pitch_range = c.unwrap_or(settings.pitch_range.unwrap_or(0.5));
-            pitch_steps = d.unwrap_or(settings.pitch_steps.unwrap_or(0.005));
-            fast_threshold = e.unwrap_or(settings.fast_threshold.unwrap_or(1.0));
-          }
-        SoundPackSettings::Basic(_) => {
-          volume = settings.volume.unwrap_or(1.0);
-          pitch_start = settings.pitch_start.unwrap_or(0.5);
-          pitch_range = settings.pitch_range.unwrap_or(0.5);
-          pitch_steps = settings.pitch_steps.unwrap_or(0.005);
-          fast_threshold = settings.fast_threshold.unwrap_or(1.0);
+        let fast = last_press.elapsed() <
+          Duration::from_millis((playback.fast_threshold * 1000.0) as u64);
+        if pitch < playback.pitch_start + playback.pitch_range && fast {
+          pitch += playback.pitch_steps;
         }
-      }
+        else if !fast {
+          pitch = playback.pitch_start;
+        }
+
+        last_press = Instant::now();
 
-      let fast = last_press.elapsed() < Duration::from_millis((fast_threshold * 1000.0) as u64);
-      if pitch < pitch_start + pitch_range && fast {
-        pitch += pitch_steps;
+        if voices.sinks.len() != playback.max_voices {
+          voices.resize(&output.handle, playback.max_voices);
+        }
+
+        let pack = &sound_packs[current_sound_pack];
+        let samples = pack.key_map.get(&key).unwrap_or(&pack.press);
+        let selection = random::<f32>() * samples.len() as f32;
+        play_with_recovery(&mut voices, &mut output, &settings.output_device,
+          playback.max_voices, &samples[selection as usize], pitch, playback.volume,
+          playback.reverb_delay_ms, playback.reverb_decay, playback.reverb_mix);
       }
-      else if !fast {
-        pitch = pitch_start;
+      InputEvent::KeyUp(key) => {
+        if !active { continue; }
+
+        let pack = &sound_packs[current_sound_pack];
+        let samples = pack.release_key_map.get(&key).unwrap_or(&pack.release);
+        if !samples.is_empty() {
+          let playback = resolve_pack_settings(&settings.sound_packs[current_sound_pack], &settings);
+          if voices.sinks.len() != playback.max_voices {
+            voices.resize(&output.handle, playback.max_voices);
+          }
+          let selection = random::<f32>() * samples.len() as f32;
+          play_with_recovery(&mut voices, &mut output, &settings.output_device,
+            playback.max_voices, &samples[selection as usize], pitch, playback.volume,
+            playback.reverb_delay_ms, playback.reverb_decay, playback.reverb_mix);
+        }
       }
+    }
+  }
+}
 
-      last_press = Instant::now();
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-      sink.stop();
-      sink.empty();
-      sink.set_speed(pitch);
-      sink.set_volume(volume);
-      sink.append(sound_packs[current_sound_pack][selection as usize].clone());
+  // Regression test for a PackSource tagging bug: an internally tagged
+  // enum can't wrap a bare string, so Url/Archive packs would panic
+  // `serde_json::from_str` on load and fail `SaveConfig` on write.
+  #[test]
+  fn pack_source_round_trips_through_json() {
+    for source in [
+      PackSource::Local(String::from("assets")),
+      PackSource::Url(String::from("https://example.com/pack.wav")),
+      PackSource::Archive(String::from("https://example.com/pack.zip"))
+    ] {
+      let json = serde_json::to_string(&source).expect("PackSource should serialize");
+      let round_tripped: PackSource = serde_json::from_str(&json)
+        .expect("PackSource should deserialize");
+      assert_eq!(source_raw(&source), source_raw(&round_tripped));
     }
-
-    previous_key_amt = keys.len();
   }
 }
@@ -0,0 +1,127 @@
+use std::io::{self, Write};
+use std::sync::mpsc::Sender;
+use crossterm::{cursor, execute, queue, terminal};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+
+use crate::InputEvent;
+
+// Snapshot of the settings the menu starts from; it only ever reports
+// changes back through `tx`, the audio loop remains the source of truth.
+pub struct MenuState {
+  pub pack_names: Vec<String>,
+  pub volume: f32,
+  pub pitch_start: f32,
+  pub pitch_range: f32,
+  pub pitch_steps: f32,
+  pub fast_threshold: f32
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Row {
+  Pack(usize),
+  Volume,
+  PitchStart,
+  PitchRange,
+  PitchSteps,
+  FastThreshold
+}
+
+// Runs the scrollable pack list / live settings menu until the user
+// quits. Blocking, so it's meant to run on its own thread.
+pub fn run(state: MenuState, tx: Sender<InputEvent>) {
+  let mut rows: Vec<Row> = (0..state.pack_names.len()).map(Row::Pack).collect();
+  rows.extend([Row::Volume, Row::PitchStart, Row::PitchRange, Row::PitchSteps, Row::FastThreshold]);
+
+  let mut volume = state.volume;
+  let mut pitch_start = state.pitch_start;
+  let mut pitch_range = state.pitch_range;
+  let mut pitch_steps = state.pitch_steps;
+  let mut fast_threshold = state.fast_threshold;
+  let mut selected = 0usize;
+
+  let mut stdout = io::stdout();
+  let _ = terminal::enable_raw_mode();
+  let _ = execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide);
+
+  loop {
+    draw(&mut stdout, &state.pack_names, selected,
+      volume, pitch_start, pitch_range, pitch_steps, fast_threshold);
+
+    if let Ok(Event::Key(key)) = event::read() {
+      if key.kind != KeyEventKind::Press { continue; }
+      match key.code {
+        KeyCode::Up => { if selected > 0 { selected -= 1; } }
+        KeyCode::Down => { if selected + 1 < rows.len() { selected += 1; } }
+        KeyCode::Enter => {
+          if let Row::Pack(idx) = rows[selected] {
+            let _ = tx.send(InputEvent::SelectPack(idx));
+          }
+        }
+        KeyCode::Left | KeyCode::Right => {
+          let step = if key.code == KeyCode::Left { -1.0 } else { 1.0 };
+          match rows[selected] {
+            Row::Pack(_) => {}
+            Row::Volume => {
+              volume = (volume + step * 0.05).max(0.0);
+              let _ = tx.send(InputEvent::SetVolume(volume));
+            }
+            Row::PitchStart => {
+              pitch_start = (pitch_start + step * 0.05).max(0.0);
+              let _ = tx.send(InputEvent::SetPitchStart(pitch_start));
+            }
+            Row::PitchRange => {
+              pitch_range = (pitch_range + step * 0.05).max(0.0);
+              let _ = tx.send(InputEvent::SetPitchRange(pitch_range));
+            }
+            Row::PitchSteps => {
+              pitch_steps = (pitch_steps + step * 0.001).max(0.0);
+              let _ = tx.send(InputEvent::SetPitchSteps(pitch_steps));
+            }
+            Row::FastThreshold => {
+              fast_threshold = (fast_threshold + step * 0.05).max(0.0);
+              let _ = tx.send(InputEvent::SetFastThreshold(fast_threshold));
+            }
+          }
+        }
+        KeyCode::Char('s') => { let _ = tx.send(InputEvent::SaveConfig); }
+        KeyCode::Char('q') | KeyCode::Esc => break,
+        _ => {}
+      }
+    }
+  }
+
+  let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+  let _ = terminal::disable_raw_mode();
+}
+
+fn draw(stdout: &mut io::Stdout, pack_names: &[String], selected: usize,
+  volume: f32, pitch_start: f32, pitch_range: f32, pitch_steps: f32, fast_threshold: f32) {
+  let _ = queue!(stdout, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0));
+
+  let mut lines = vec![
+    String::from("KSFX -- arrows to navigate, Enter to activate a pack, Left/Right to adjust, s to save, q to quit"),
+    String::new()
+  ];
+  for (idx, name) in pack_names.iter().enumerate() {
+    let marker = if idx == selected { ">" } else { " " };
+    lines.push(format!("{} {}", marker, name));
+  }
+
+  let fields: [(&str, f32); 5] = [
+    ("Volume", volume),
+    ("Pitch start", pitch_start),
+    ("Pitch range", pitch_range),
+    ("Pitch steps", pitch_steps),
+    ("Fast threshold", fast_threshold)
+  ];
+  for (offset, (label, value)) in fields.iter().enumerate() {
+    let row = pack_names.len() + offset;
+    let marker = if row == selected { ">" } else { " " };
+    lines.push(format!("{} {}: {:.3}", marker, label, value));
+  }
+
+  for line in lines {
+    print!("{}\r\n", line);
+  }
+  let _ = stdout.flush();
+}